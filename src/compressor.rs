@@ -0,0 +1,350 @@
+//! Pluggable block compressors for [`ParGz`](crate::ParGz).
+//!
+//! [`ParGz`](crate::ParGz) splits the input into independent blocks and hands each one to a worker
+//! on the threadpool. The worker compresses the block with a [`Compressor`] and the resulting bytes
+//! are written to the underlying writer in order. Because each block is compressed on its own, every
+//! built-in format emits a self-framed unit per block (a gzip member, a zstd/bzip2/xz frame, ...).
+//!
+//! For [`Gzip`], [`Zstd`], [`Bzip2`], and [`Xz`] the concatenation of those units is itself a valid
+//! stream in that format, read back with a multi-member/multi-frame decoder (for example
+//! [`MultiGzDecoder`](flate2::bufread::MultiGzDecoder) or
+//! [`XzDecoder::new_multi_decoder`](xz2::read::XzDecoder::new_multi_decoder)). [`Zlib`], [`Deflate`],
+//! and [`Lz4`] have no multi-stream framing, so their concatenated per-block streams are *not* a
+//! single stream: decode that output one frame at a time, feeding each decoder the bytes left over
+//! from the previous frame (see those types' docs).
+//!
+//! The default format is [`Gzip`], which reproduces the original behavior of the crate.
+use std::io::{self, Read, Write};
+
+use flate2::bufread::{DeflateEncoder, GzEncoder, ZlibEncoder};
+
+use crate::{Compression, ParGzError};
+
+/// A block compressor that [`ParGz`](crate::ParGz) can target.
+///
+/// Implementations turn a single buffered block into a self-framed chunk of the target format (e.g.
+/// a full gzip member or zstd frame per block). The chunks produced for consecutive blocks are
+/// written back-to-back; whether that concatenation is itself one valid stream depends on the format
+/// ([`Gzip`], [`Zstd`], [`Bzip2`] and [`Xz`] yes; [`Zlib`], [`Deflate`] and [`Lz4`] no).
+pub trait Compressor: Clone + Send + 'static {
+    /// Whether this format produces gzip members. Only gzip supports the single-stream
+    /// [`pigz_compatible`](crate::ParGzBuilder::pigz_compatible) mode.
+    const IS_GZIP: bool = false;
+
+    /// Compress a single block, returning the framed bytes to write to the output.
+    fn compress_block(&self, input: &[u8], level: Compression) -> Result<Vec<u8>, ParGzError>;
+
+    /// Frame `input` as a self-contained unit *without* compressing it, if the format supports a
+    /// stored (uncompressed) representation.
+    ///
+    /// [`ParGz`](crate::ParGz) uses this to skip compression for blocks below the configured
+    /// minimum size and to fall back when the stored framing would be smaller than the compressed
+    /// output (see [`stored_size`](Compressor::stored_size)). Formats without a
+    /// stored representation return `None` and are always compressed.
+    fn stored_block(&self, _input: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// The exact byte length [`stored_block`](Compressor::stored_block) would produce for an input of
+    /// `input_len` bytes, computed arithmetically from the framing so the compressible common path
+    /// can compare sizes without building (and CRC-ing) a stored member it then throws away.
+    ///
+    /// Must return `Some` exactly when [`stored_block`](Compressor::stored_block) does, with a length
+    /// matching the bytes that method would emit.
+    fn stored_size(&self, _input_len: usize) -> Option<usize> {
+        None
+    }
+}
+
+/// Gzip output, one gzip member per block. This is the default and original behavior.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Gzip;
+
+impl Compressor for Gzip {
+    const IS_GZIP: bool = true;
+
+    fn compress_block(&self, input: &[u8], level: Compression) -> Result<Vec<u8>, ParGzError> {
+        let mut buffer = Vec::with_capacity(input.len());
+        let mut encoder: GzEncoder<&[u8]> = GzEncoder::new(input, level);
+        encoder.read_to_end(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn stored_block(&self, input: &[u8]) -> Option<Vec<u8>> {
+        Some(gzip_stored_member(input))
+    }
+
+    fn stored_size(&self, input_len: usize) -> Option<usize> {
+        Some(gzip_stored_size(input_len))
+    }
+}
+
+/// The largest payload a single DEFLATE stored block can hold.
+const MAX_STORED: usize = 0xffff;
+
+/// The byte length of the gzip member [`gzip_stored_member`] produces for `input_len` bytes: a
+/// 10-byte header, a 5-byte framing prefix per stored block, the payload, and the 8-byte trailer.
+fn gzip_stored_size(input_len: usize) -> usize {
+    // Even an empty input emits one (empty, final) stored block.
+    let blocks = input_len.div_ceil(MAX_STORED).max(1);
+    crate::pigz::GZIP_HEADER.len() + 5 * blocks + input_len + 8
+}
+
+/// Build a valid gzip member whose DEFLATE payload is one or more stored (uncompressed) blocks.
+///
+/// DEFLATE stores data as a sequence of blocks, each at most `0xffff` bytes, so long inputs are
+/// split across several stored blocks with only the last marked final.
+fn gzip_stored_member(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(gzip_stored_size(input.len()));
+    out.extend_from_slice(&crate::pigz::GZIP_HEADER);
+
+    if input.is_empty() {
+        // A single empty, final stored block.
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xff, 0xff]);
+    } else {
+        let mut chunks = input.chunks(MAX_STORED).peekable();
+        while let Some(chunk) = chunks.next() {
+            let final_block = chunks.peek().is_none();
+            out.push(if final_block { 0x01 } else { 0x00 });
+            let len = chunk.len() as u16;
+            out.extend_from_slice(&len.to_le_bytes());
+            out.extend_from_slice(&(!len).to_le_bytes());
+            out.extend_from_slice(chunk);
+        }
+    }
+
+    let crc = crate::pigz::crc32(input);
+    out.extend_from_slice(&crc.to_le_bytes());
+    out.extend_from_slice(&((input.len() as u64 & 0xffff_ffff) as u32).to_le_bytes());
+    out
+}
+
+/// Zlib output, one zlib stream per block.
+///
+/// Like [`Deflate`] and [`Lz4`], the per-block streams do not form a single stream when
+/// concatenated; decode the output by reading one stream at a time (e.g. looping a
+/// `ZlibDecoder` and feeding it the bytes left over from the previous stream).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Zlib;
+
+impl Compressor for Zlib {
+    fn compress_block(&self, input: &[u8], level: Compression) -> Result<Vec<u8>, ParGzError> {
+        let mut buffer = Vec::with_capacity(input.len());
+        let mut encoder: ZlibEncoder<&[u8]> = ZlibEncoder::new(input, level);
+        encoder.read_to_end(&mut buffer)?;
+        Ok(buffer)
+    }
+}
+
+/// Raw DEFLATE output, one deflate stream per block.
+///
+/// As with [`Zlib`] and [`Lz4`], concatenated blocks are not a single stream; decode one block at a
+/// time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Deflate;
+
+impl Compressor for Deflate {
+    fn compress_block(&self, input: &[u8], level: Compression) -> Result<Vec<u8>, ParGzError> {
+        let mut buffer = Vec::with_capacity(input.len());
+        let mut encoder: DeflateEncoder<&[u8]> = DeflateEncoder::new(input, level);
+        encoder.read_to_end(&mut buffer)?;
+        Ok(buffer)
+    }
+}
+
+/// Zstandard output, one zstd frame per block.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Zstd;
+
+impl Compressor for Zstd {
+    fn compress_block(&self, input: &[u8], level: Compression) -> Result<Vec<u8>, ParGzError> {
+        // zstd levels run 1..=22; map the flate2 0..=9 level onto a reasonable zstd level.
+        let level = (level.level() as i32).clamp(1, 22);
+        Ok(zstd::stream::encode_all(input, level)?)
+    }
+}
+
+/// Bzip2 output, one bzip2 stream per block.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bzip2;
+
+impl Compressor for Bzip2 {
+    fn compress_block(&self, input: &[u8], level: Compression) -> Result<Vec<u8>, ParGzError> {
+        let level = bzip2::Compression::new(level.level().clamp(1, 9));
+        let mut buffer = Vec::with_capacity(input.len());
+        let mut encoder = bzip2::read::BzEncoder::new(input, level);
+        encoder.read_to_end(&mut buffer)?;
+        Ok(buffer)
+    }
+}
+
+/// Xz (LZMA2) output, one xz stream per block.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Xz;
+
+impl Compressor for Xz {
+    fn compress_block(&self, input: &[u8], level: Compression) -> Result<Vec<u8>, ParGzError> {
+        let mut buffer = Vec::with_capacity(input.len());
+        let mut encoder = xz2::read::XzEncoder::new(input, level.level());
+        encoder.read_to_end(&mut buffer)?;
+        Ok(buffer)
+    }
+}
+
+/// Lz4 output, one lz4 frame per block.
+///
+/// A single-frame `lz4::Decoder` reads only the first block's frame, so -- as with multi-member
+/// gzip and [`MultiGzDecoder`](flate2::bufread::MultiGzDecoder) -- decode the output by iterating
+/// frames, feeding each decoder the bytes left after the previous frame.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Lz4;
+
+impl Compressor for Lz4 {
+    fn compress_block(&self, input: &[u8], _level: Compression) -> Result<Vec<u8>, ParGzError> {
+        let mut encoder = lz4::EncoderBuilder::new().build(Vec::with_capacity(input.len()))?;
+        encoder.write_all(input)?;
+        let (buffer, result) = encoder.finish();
+        result?;
+        Ok(buffer)
+    }
+}
+
+/// Snappy output, one snappy frame per block.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Snap;
+
+impl Compressor for Snap {
+    fn compress_block(&self, input: &[u8], _level: Compression) -> Result<Vec<u8>, ParGzError> {
+        let mut buffer = Vec::with_capacity(input.len());
+        {
+            let mut writer = snap::write::FrameEncoder::new(&mut buffer);
+            writer.write_all(input)?;
+            writer
+                .into_inner()
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        }
+        Ok(buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Three blocks, the same way `ParGz` would hand them to a compressor one at a time.
+    const BLOCKS: [&[u8]; 3] = [
+        b"the quick brown fox jumps over the lazy dog",
+        b"pack my box with five dozen liquor jugs",
+        b"how vexingly quick daft zebras jump",
+    ];
+
+    fn whole() -> Vec<u8> {
+        BLOCKS.concat()
+    }
+
+    /// Compress every block independently and concatenate, mirroring the writer's output.
+    fn compress_all<C: Compressor>(compressor: &C) -> Vec<u8> {
+        let mut out = Vec::new();
+        for block in BLOCKS {
+            out.extend_from_slice(
+                &compressor
+                    .compress_block(block, Compression::new(6))
+                    .unwrap(),
+            );
+        }
+        out
+    }
+
+    /// Decode a stream of independently-framed units by handing each decoder the bytes left over
+    /// from the previous one, as the per-block formats require.
+    fn decode_framed<F>(mut data: &[u8], mut decode_one: F) -> Vec<u8>
+    where
+        F: FnMut(&[u8]) -> (Vec<u8>, usize),
+    {
+        let mut out = Vec::new();
+        while !data.is_empty() {
+            let (decoded, consumed) = decode_one(data);
+            assert!(consumed > 0, "decoder failed to advance");
+            out.extend_from_slice(&decoded);
+            data = &data[consumed..];
+        }
+        out
+    }
+
+    #[test]
+    fn test_gzip_roundtrip() {
+        let mut decoder = flate2::bufread::MultiGzDecoder::new(&compress_all(&Gzip)[..]);
+        let mut out = vec![];
+        decoder.read_to_end(&mut out).unwrap();
+        assert_eq!(out, whole());
+    }
+
+    #[test]
+    fn test_zlib_roundtrip() {
+        let out = decode_framed(&compress_all(&Zlib), |data| {
+            let mut decoder = flate2::bufread::ZlibDecoder::new(data);
+            let mut buf = vec![];
+            decoder.read_to_end(&mut buf).unwrap();
+            let consumed = data.len() - decoder.into_inner().len();
+            (buf, consumed)
+        });
+        assert_eq!(out, whole());
+    }
+
+    #[test]
+    fn test_deflate_roundtrip() {
+        let out = decode_framed(&compress_all(&Deflate), |data| {
+            let mut decoder = flate2::bufread::DeflateDecoder::new(data);
+            let mut buf = vec![];
+            decoder.read_to_end(&mut buf).unwrap();
+            let consumed = data.len() - decoder.into_inner().len();
+            (buf, consumed)
+        });
+        assert_eq!(out, whole());
+    }
+
+    #[test]
+    fn test_zstd_roundtrip() {
+        // `decode_all` reads through the concatenated frames, exercising the level mapping too.
+        let out = zstd::stream::decode_all(&compress_all(&Zstd)[..]).unwrap();
+        assert_eq!(out, whole());
+    }
+
+    #[test]
+    fn test_bzip2_roundtrip() {
+        let mut decoder = bzip2::read::MultiBzDecoder::new(&compress_all(&Bzip2)[..]);
+        let mut out = vec![];
+        decoder.read_to_end(&mut out).unwrap();
+        assert_eq!(out, whole());
+    }
+
+    #[test]
+    fn test_xz_roundtrip() {
+        let mut decoder = xz2::read::XzDecoder::new_multi_decoder(&compress_all(&Xz)[..]);
+        let mut out = vec![];
+        decoder.read_to_end(&mut out).unwrap();
+        assert_eq!(out, whole());
+    }
+
+    #[test]
+    fn test_lz4_roundtrip() {
+        let out = decode_framed(&compress_all(&Lz4), |data| {
+            let mut decoder = lz4::Decoder::new(data).unwrap();
+            let mut buf = vec![];
+            decoder.read_to_end(&mut buf).unwrap();
+            let (remaining, result) = decoder.finish();
+            result.unwrap();
+            let consumed = data.len() - remaining.len();
+            (buf, consumed)
+        });
+        assert_eq!(out, whole());
+    }
+
+    #[test]
+    fn test_snap_roundtrip() {
+        let mut decoder = snap::read::FrameDecoder::new(&compress_all(&Snap)[..]);
+        let mut out = vec![];
+        decoder.read_to_end(&mut out).unwrap();
+        assert_eq!(out, whole());
+    }
+}
@@ -25,27 +25,49 @@
 //!
 //! fn main() {
 //!     let mut writer = vec![];
-//!     let mut par_gz = ParGz::builder(writer).build();
+//!     let mut par_gz = ParGz::builder(writer).build().unwrap();
 //!     par_gz.write_all(b"This is a first test line\n").unwrap();
 //!     par_gz.write_all(b"This is a second test line\n").unwrap();
 //!     par_gz.finish().unwrap();
 //! }
 //! ```
-use std::io::{self, Read, Write};
+//!
+//! A different output format can be selected on the builder:
+//!
+//! ```no_run
+//! use std::io::Write;
+//!
+//! use gzp::{ParGz, Zstd};
+//!
+//! let mut writer = vec![];
+//! let mut par = ParGz::builder(writer).format(Zstd).build().unwrap();
+//! par.write_all(b"hello world").unwrap();
+//! par.finish().unwrap();
+//! ```
+use std::io::{self, Write};
 
 use bytes::BytesMut;
-use flate2::bufread::GzEncoder;
 pub use flate2::Compression;
 use futures::executor::block_on;
 use thiserror::Error;
 use tokio::sync::mpsc::{self, Receiver, Sender};
 
+pub use crate::compressor::{
+    Bzip2, Compressor, Deflate, Gzip, Lz4, Snap, Xz, Zlib, Zstd,
+};
+pub use crate::reader::{Algorithm, DetectDecoder, ParGzReader, ParGzReaderBuilder};
+
+mod compressor;
+mod pigz;
+pub mod pool;
+mod reader;
+
 /// 128 KB default buffer size, same as pigz
-const BUFSIZE: usize = 64 * (1 << 10) * 2;
+pub(crate) const BUFSIZE: usize = 64 * (1 << 10) * 2;
 
 /// The [`ParGz`] builder.
 #[derive(Debug)]
-pub struct ParGzBuilder<W> {
+pub struct ParGzBuilder<W, C = Gzip> {
     /// The level to compress the output. Defaults to `3`.
     compression_level: Compression,
     /// The buffersize accumulate before trying to compress it. Defaults to [`BUFSIZE`].
@@ -54,9 +76,16 @@ pub struct ParGzBuilder<W> {
     writer: W,
     /// The number of threads to use for compression. Defaults to all available threads.
     num_threads: usize,
+    /// The [`Compressor`] used to compress each block. Defaults to [`Gzip`].
+    compressor: C,
+    /// Emit a single gzip member with a carry-over dictionary instead of concatenated members.
+    /// Defaults to `false`. Only supported with the [`Gzip`] format; `build` fails otherwise.
+    pigz_compatible: bool,
+    /// Blocks smaller than this are stored uncompressed rather than compressed. Defaults to `0`.
+    min_compress_size: usize,
 }
 
-impl<W> ParGzBuilder<W>
+impl<W> ParGzBuilder<W, Gzip>
 where
     W: Send + Write + 'static,
 {
@@ -67,8 +96,18 @@ where
             buffer_size: BUFSIZE,
             writer,
             num_threads: num_cpus::get(),
+            compressor: Gzip,
+            pigz_compatible: false,
+            min_compress_size: 0,
         }
     }
+}
+
+impl<W, C> ParGzBuilder<W, C>
+where
+    W: Send + Write + 'static,
+    C: Compressor,
+{
 
     /// Set the [`buffer_size`](ParGzBuilder.buffer_size).
     pub fn buffer_size(mut self, buffer_size: usize) -> Self {
@@ -90,19 +129,86 @@ where
         self
     }
 
+    /// Set the output [`format`](ParGzBuilder.compressor), e.g. [`Gzip`], [`Zstd`], or [`Lz4`].
+    pub fn format<C2>(self, compressor: C2) -> ParGzBuilder<W, C2>
+    where
+        C2: Compressor,
+    {
+        ParGzBuilder {
+            compression_level: self.compression_level,
+            buffer_size: self.buffer_size,
+            writer: self.writer,
+            num_threads: self.num_threads,
+            compressor,
+            pigz_compatible: self.pigz_compatible,
+            min_compress_size: self.min_compress_size,
+        }
+    }
+
+    /// Produce a single valid gzip member with a carry-over dictionary and combined CRC, rather
+    /// than concatenated independent members.
+    ///
+    /// This closes the two [known differences from pigz](crate#known-differences-from-pigz): each
+    /// block is compressed with the previous block's trailing window as a preset dictionary, and
+    /// the per-block CRCs are folded into one member CRC. Only the [`Gzip`] format supports this
+    /// flag; with any other format [`build`](ParGzBuilder::build) returns an error.
+    pub fn pigz_compatible(mut self, pigz_compatible: bool) -> Self {
+        self.pigz_compatible = pigz_compatible;
+        self
+    }
+
+    /// Set the [`min_compress_size`](ParGzBuilder.min_compress_size).
+    ///
+    /// Blocks smaller than `min_compress_size` bytes are stored uncompressed (in a valid gzip
+    /// member) instead of being run through the compressor, which avoids wasted CPU and pathological
+    /// expansion on tiny payloads. Regardless of this threshold, any block whose stored framing would
+    /// be smaller than its compressed form is also emitted stored.
+    ///
+    /// This only affects the [`Gzip`] format, the one built-in [`Compressor`] with a stored
+    /// representation. With any other format the threshold has no effect and every block is
+    /// compressed. In [`pigz_compatible`](ParGzBuilder::pigz_compatible) mode neither the threshold
+    /// nor the stored fallback applies: every block is compressed as raw DEFLATE.
+    pub fn min_compress_size(mut self, min_compress_size: usize) -> Self {
+        self.min_compress_size = min_compress_size;
+        self
+    }
+
     /// Create a configured [`ParGz`] object.
-    pub fn build(self) -> ParGz {
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if [`pigz_compatible`](ParGzBuilder::pigz_compatible) is set for a
+    /// non-[`Gzip`] format, since the single-stream mode only produces gzip output.
+    pub fn build(self) -> Result<ParGz<C>, ParGzError> {
+        if self.pigz_compatible && !C::IS_GZIP {
+            return Err(ParGzError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "pigz_compatible is only supported for the Gzip format",
+            )));
+        }
         let (tx, rx) = mpsc::channel(self.num_threads);
         let buffer_size = self.buffer_size;
+        let compressor = self.compressor;
+        let pigz_compatible = self.pigz_compatible;
+        let min_compress_size = self.min_compress_size;
         let handle = std::thread::spawn(move || {
-            ParGz::run(rx, self.writer, self.num_threads, self.compression_level)
+            ParGz::run(
+                rx,
+                self.writer,
+                self.num_threads,
+                self.compression_level,
+                compressor,
+                pigz_compatible,
+                min_compress_size,
+            )
         });
-        ParGz {
+        Ok(ParGz {
             handle,
             tx,
             buffer: BytesMut::with_capacity(buffer_size),
             buffer_size,
-        }
+            marker: std::marker::PhantomData,
+        })
     }
 }
 
@@ -118,22 +224,87 @@ pub enum ParGzError {
     Unknown,
 }
 
-pub struct ParGz {
+/// A single raw-DEFLATE block produced by the pigz-compatible pipeline.
+struct PigzBlock {
+    /// The sync-flushed raw DEFLATE bytes for this block.
+    deflate: Vec<u8>,
+    /// The CRC32 of the block's uncompressed input.
+    crc: u32,
+    /// The number of uncompressed bytes in this block.
+    len: usize,
+}
+
+/// Compress one block as raw DEFLATE using `dict` as a preset dictionary, ending on a byte
+/// boundary with a sync flush so it can be concatenated with the following block.
+fn compress_pigz_block(
+    input: &[u8],
+    dict: &[u8],
+    compression_level: Compression,
+) -> Result<PigzBlock, ParGzError> {
+    use flate2::{Compress, FlushCompress};
+
+    let mut compress = Compress::new(compression_level, false);
+    if !dict.is_empty() {
+        compress
+            .set_dictionary(dict)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    }
+
+    let mut deflate = Vec::with_capacity(input.len());
+    // Drive all of the input through, then sync-flush so the block ends on a byte boundary.
+    while (compress.total_in() as usize) < input.len() {
+        let before = compress.total_in() as usize;
+        if deflate.len() == deflate.capacity() {
+            deflate.reserve(input.len().max(1));
+        }
+        compress
+            .compress_vec(&input[before..], &mut deflate, FlushCompress::None)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        // Guard against a stall where no input was consumed and no output produced.
+        if (compress.total_in() as usize) == before {
+            deflate.reserve(input.len().max(1));
+        }
+    }
+    loop {
+        let out_before = deflate.len();
+        deflate.reserve(64);
+        compress
+            .compress_vec(&[], &mut deflate, FlushCompress::Sync)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        if deflate.len() == out_before {
+            break;
+        }
+    }
+
+    Ok(PigzBlock {
+        crc: pigz::crc32(input),
+        len: input.len(),
+        deflate,
+    })
+}
+
+pub struct ParGz<C = Gzip> {
     handle: std::thread::JoinHandle<Result<(), ParGzError>>,
     tx: Sender<BytesMut>,
     buffer: BytesMut,
     buffer_size: usize,
+    marker: std::marker::PhantomData<C>,
 }
 
-impl ParGz {
+impl ParGz<Gzip> {
     /// Create a builder to configure the [`ParGz`] runtime.
-    pub fn builder<W>(writer: W) -> ParGzBuilder<W>
+    pub fn builder<W>(writer: W) -> ParGzBuilder<W, Gzip>
     where
         W: Write + Send + 'static,
     {
         ParGzBuilder::new(writer)
     }
+}
 
+impl<C> ParGz<C>
+where
+    C: Compressor,
+{
     /// Launch the tokio runtime that coordinates the threadpool that does the following:
     ///
     /// 1. Receives chunks of bytes from from the [`ParGz::write`] method.
@@ -141,10 +312,37 @@ impl ParGz {
     /// 3. Send the future for that task to the writer.
     /// 4. Write the bytes to the underlying writer.
     fn run<W>(
+        rx: Receiver<BytesMut>,
+        writer: W,
+        num_threads: usize,
+        compression_level: Compression,
+        compressor: C,
+        pigz_compatible: bool,
+        min_compress_size: usize,
+    ) -> Result<(), ParGzError>
+    where
+        W: Write + Send + 'static,
+    {
+        if pigz_compatible {
+            return Self::run_pigz(rx, writer, num_threads, compression_level);
+        }
+        Self::run_members(
+            rx,
+            writer,
+            num_threads,
+            compression_level,
+            compressor,
+            min_compress_size,
+        )
+    }
+
+    fn run_members<W>(
         mut rx: Receiver<BytesMut>,
         mut writer: W,
         num_threads: usize,
         compression_level: Compression,
+        compressor: C,
+        min_compress_size: usize,
     ) -> Result<(), ParGzError>
     where
         W: Write + Send + 'static,
@@ -156,16 +354,31 @@ impl ParGz {
         // Spawn the main task
         rt.block_on(async {
             let (out_sender, mut out_receiver) = mpsc::channel(num_threads);
-            let compressor = tokio::task::spawn(async move {
+            let reader = tokio::task::spawn(async move {
                 while let Some(chunk) = rx.recv().await {
+                    let compressor = compressor.clone();
                     let task =
                         tokio::task::spawn_blocking(move || -> Result<Vec<u8>, ParGzError> {
-                            let mut buffer = Vec::with_capacity(chunk.len());
-                            let mut gz: GzEncoder<&[u8]> =
-                                GzEncoder::new(&chunk[..], compression_level);
-                            gz.read_to_end(&mut buffer)?;
-
-                            Ok(buffer)
+                            // Skip compression entirely for blocks under the threshold.
+                            if chunk.len() < min_compress_size {
+                                if let Some(stored) = compressor.stored_block(&chunk[..]) {
+                                    return Ok(stored);
+                                }
+                            }
+                            let compressed =
+                                compressor.compress_block(&chunk[..], compression_level)?;
+                            // Fall back to a stored block only when its framing is actually smaller
+                            // than the compressed member. The stored size is computed arithmetically
+                            // so the common (compressible) path neither allocates a stored member nor
+                            // runs an extra CRC pass just to lose the comparison.
+                            if let Some(stored_size) = compressor.stored_size(chunk.len()) {
+                                if stored_size < compressed.len() {
+                                    if let Some(stored) = compressor.stored_block(&chunk[..]) {
+                                        return Ok(stored);
+                                    }
+                                }
+                            }
+                            Ok(compressed)
                         });
                     out_sender
                         .send(task)
@@ -184,7 +397,78 @@ impl ParGz {
                 Ok(())
             });
 
-            compressor.await??;
+            reader.await??;
+            writer_task.await??;
+            Ok::<(), ParGzError>(())
+        })
+    }
+
+    /// The single-member variant of [`run`](ParGz::run) used when `pigz_compatible` is set.
+    ///
+    /// A single gzip header is written up front and each block is compressed as raw DEFLATE using
+    /// the previous block's trailing window as a preset dictionary, terminated with a sync flush so
+    /// the blocks concatenate. The per-block CRCs and lengths are folded together in order to form
+    /// the final gzip trailer.
+    fn run_pigz<W>(
+        mut rx: Receiver<BytesMut>,
+        mut writer: W,
+        num_threads: usize,
+        compression_level: Compression,
+    ) -> Result<(), ParGzError>
+    where
+        W: Write + Send + 'static,
+    {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(num_threads)
+            .build()?;
+
+        rt.block_on(async {
+            let (out_sender, mut out_receiver) = mpsc::channel(num_threads);
+
+            let reader = tokio::task::spawn(async move {
+                // The preset dictionary for block N is the trailing window of block N-1's raw
+                // input, which is available as soon as block N-1 is read -- so the blocks can still
+                // be compressed concurrently.
+                let mut dictionary: Vec<u8> = Vec::new();
+                while let Some(chunk) = rx.recv().await {
+                    let dict = dictionary.clone();
+                    // Carry the trailing window forward before handing the chunk off.
+                    let tail_start = chunk.len().saturating_sub(pigz::DICT_SIZE);
+                    dictionary = chunk[tail_start..].to_vec();
+
+                    let task =
+                        tokio::task::spawn_blocking(move || -> Result<PigzBlock, ParGzError> {
+                            compress_pigz_block(&chunk[..], &dict, compression_level)
+                        });
+                    out_sender
+                        .send(task)
+                        .await
+                        .map_err(|_e| ParGzError::ChannelSend)?;
+                }
+                Ok::<(), ParGzError>(())
+            });
+
+            let writer_task = tokio::task::spawn_blocking(move || -> Result<(), ParGzError> {
+                writer.write_all(&pigz::GZIP_HEADER)?;
+
+                let mut crc = 0u32;
+                let mut total_len = 0u64;
+                while let Some(task) = block_on(out_receiver.recv()) {
+                    let block = block_on(task)??;
+                    writer.write_all(&block.deflate)?;
+                    crc = pigz::crc32_combine(crc, block.crc, block.len as u64);
+                    total_len += block.len as u64;
+                }
+
+                // Terminate the deflate stream and write the gzip trailer.
+                writer.write_all(&pigz::FINAL_BLOCK)?;
+                writer.write_all(&crc.to_le_bytes())?;
+                writer.write_all(&((total_len & 0xffff_ffff) as u32).to_le_bytes())?;
+                writer.flush()?;
+                Ok(())
+            });
+
+            reader.await??;
             writer_task.await??;
             Ok::<(), ParGzError>(())
         })
@@ -200,7 +484,10 @@ impl ParGz {
     }
 }
 
-impl Write for ParGz {
+impl<C> Write for ParGz<C>
+where
+    C: Compressor,
+{
     /// Write a buffer into this writer, returning how many bytes were written.
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         self.buffer.extend_from_slice(buf);
@@ -226,7 +513,7 @@ impl Write for ParGz {
 mod test {
     use std::{
         fs::File,
-        io::{BufReader, BufWriter},
+        io::{BufReader, BufWriter, Read},
     };
 
     use flate2::bufread::MultiGzDecoder;
@@ -250,7 +537,7 @@ mod test {
         ";
 
         // Compress input to output
-        let mut par_gz = ParGz::builder(out_writer).build();
+        let mut par_gz = ParGz::builder(out_writer).build().unwrap();
         par_gz.write_all(input).unwrap();
         par_gz.finish().unwrap();
 
@@ -296,7 +583,8 @@ mod test {
             .buffer_size(205)
             .num_threads(3)
             .compression_level(Compression::new(2))
-            .build();
+            .build()
+            .unwrap();
         par_gz.write_all(&input).unwrap();
         par_gz.finish().unwrap();
 
@@ -314,6 +602,72 @@ mod test {
         assert_eq!(input.to_vec(), bytes);
     }
 
+    #[test]
+    fn test_pigz_compatible() {
+        let dir = tempdir().unwrap();
+
+        // Create output file
+        let output_file = dir.path().join("output.txt");
+        let out_writer = BufWriter::new(File::create(&output_file).unwrap());
+
+        // Use a small buffer so the carry-over dictionary spans several blocks.
+        let input = b"
+        This is a longer test than normal to come up with a bunch of text.
+        We'll read just a few lines at a time, repeated over and over so the
+        dictionary carried between blocks actually does something useful.
+        ";
+
+        let mut par_gz = ParGz::builder(out_writer)
+            .buffer_size(32)
+            .num_threads(2)
+            .pigz_compatible(true)
+            .build()
+            .unwrap();
+        par_gz.write_all(input).unwrap();
+        par_gz.finish().unwrap();
+
+        // Read output back in
+        let mut reader = BufReader::new(File::open(output_file).unwrap());
+        let mut result = vec![];
+        reader.read_to_end(&mut result).unwrap();
+
+        // A single gzip member should decode with a plain (non-multi) decoder.
+        let mut gz = flate2::bufread::GzDecoder::new(&result[..]);
+        let mut bytes = vec![];
+        gz.read_to_end(&mut bytes).unwrap();
+
+        assert_eq!(input.to_vec(), bytes);
+    }
+
+    #[test]
+    fn test_min_compress_size_stored() {
+        let dir = tempdir().unwrap();
+
+        let output_file = dir.path().join("output.txt");
+        let out_writer = BufWriter::new(File::create(&output_file).unwrap());
+
+        let input = b"tiny block that will be stored rather than compressed";
+
+        // A threshold larger than the block forces the stored-block fallback.
+        let mut par_gz = ParGz::builder(out_writer)
+            .buffer_size(16)
+            .min_compress_size(1 << 20)
+            .build()
+            .unwrap();
+        par_gz.write_all(input).unwrap();
+        par_gz.finish().unwrap();
+
+        let mut reader = BufReader::new(File::open(output_file).unwrap());
+        let mut result = vec![];
+        reader.read_to_end(&mut result).unwrap();
+
+        // Stored blocks are still wrapped in valid gzip members.
+        let mut gz = MultiGzDecoder::new(&result[..]);
+        let mut bytes = vec![];
+        gz.read_to_end(&mut bytes).unwrap();
+        assert_eq!(input.to_vec(), bytes);
+    }
+
     proptest! {
         #[test]
         fn test_all(
@@ -334,7 +688,8 @@ mod test {
             .buffer_size(buf_size)
             .compression_level(Compression::new(comp_lvl))
             .num_threads(num_threads)
-            .build();
+            .build()
+            .unwrap();
         par_gz.write_all(&input).unwrap();
         par_gz.finish().unwrap();
 
@@ -0,0 +1,115 @@
+//! Helpers for the single-stream, pigz-compatible output mode.
+//!
+//! In this mode [`ParGz`](crate::ParGz) emits a single valid gzip member instead of one member per
+//! block. Each block is compressed as raw DEFLATE using the previous block's trailing 32 KiB as a
+//! preset dictionary and terminated with a sync flush so it ends on a byte boundary and can be
+//! concatenated with the next block. The per-block CRC32 values are folded together with the zlib
+//! `crc32_combine` algorithm to produce the member's final CRC.
+use crc32fast::Hasher;
+
+/// The size of the sliding window carried between blocks as a preset dictionary.
+pub(crate) const DICT_SIZE: usize = 32 * 1024;
+
+/// A fixed, minimal 10-byte gzip header (deflate, no extra fields, unknown mtime, unknown OS).
+pub(crate) const GZIP_HEADER: [u8; 10] = [0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff];
+
+/// An empty, final DEFLATE block used to terminate the stream after the sync-flushed blocks.
+pub(crate) const FINAL_BLOCK: [u8; 2] = [0x03, 0x00];
+
+/// The CRC32 of a single block of input.
+pub(crate) fn crc32(input: &[u8]) -> u32 {
+    let mut hasher = Hasher::new();
+    hasher.update(input);
+    hasher.finalize()
+}
+
+/// Combine two CRC32 values as if the `len2`-byte input behind `crc2` were appended to the input
+/// behind `crc1`, following zlib's `crc32_combine`.
+pub(crate) fn crc32_combine(mut crc1: u32, crc2: u32, mut len2: u64) -> u32 {
+    if len2 == 0 {
+        return crc1;
+    }
+
+    // `odd` starts as the operator for a single zero bit; `even` is built by squaring it.
+    let mut even = [0u32; 32];
+    let mut odd = [0u32; 32];
+
+    // Put the CRC-32 polynomial (reflected) in the first slot, then the identity in the rest.
+    odd[0] = 0xedb8_8320;
+    let mut row = 1u32;
+    for slot in odd.iter_mut().skip(1) {
+        *slot = row;
+        row <<= 1;
+    }
+
+    // odd = operator for one zero bit, even = two zero bits, odd = four zero bits. The first
+    // squaring in the loop below yields the operator for one zero byte (eight bits).
+    gf2_matrix_square(&mut even, &odd);
+    gf2_matrix_square(&mut odd, &even);
+
+    // Apply the zero-byte operators for each set bit of len2, squaring each time to double the run.
+    loop {
+        gf2_matrix_square(&mut even, &odd);
+        if len2 & 1 != 0 {
+            crc1 = gf2_matrix_times(&even, crc1);
+        }
+        len2 >>= 1;
+        if len2 == 0 {
+            break;
+        }
+
+        gf2_matrix_square(&mut odd, &even);
+        if len2 & 1 != 0 {
+            crc1 = gf2_matrix_times(&odd, crc1);
+        }
+        len2 >>= 1;
+        if len2 == 0 {
+            break;
+        }
+    }
+
+    crc1 ^ crc2
+}
+
+/// Multiply the GF(2) vector `vec` by the operator matrix `mat`.
+fn gf2_matrix_times(mat: &[u32; 32], mut vec: u32) -> u32 {
+    let mut sum = 0;
+    let mut index = 0;
+    while vec != 0 {
+        if vec & 1 != 0 {
+            sum ^= mat[index];
+        }
+        vec >>= 1;
+        index += 1;
+    }
+    sum
+}
+
+/// Square the operator matrix `mat` into `square` (i.e. build the operator for twice the run).
+fn gf2_matrix_square(square: &mut [u32; 32], mat: &[u32; 32]) {
+    for n in 0..32 {
+        square[n] = gf2_matrix_times(mat, mat[n]);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crc32_combine_matches_whole() {
+        let a = b"first half of the data";
+        let b = b"the second half of the data";
+        let mut whole = a.to_vec();
+        whole.extend_from_slice(b);
+
+        let combined = crc32_combine(crc32(a), crc32(b), b.len() as u64);
+        assert_eq!(combined, crc32(&whole));
+    }
+
+    #[test]
+    fn test_crc32_combine_empty() {
+        let a = b"some data";
+        assert_eq!(crc32_combine(crc32(a), crc32(b""), 0), crc32(a));
+    }
+}
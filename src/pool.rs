@@ -0,0 +1,314 @@
+//! A pool of compression threads that fans out to many independent output writers.
+//!
+//! [`ParGz`](crate::ParGz) dedicates a runtime to a single writer. When compressing to many outputs
+//! at once -- hundreds of gzipped shards, say -- that means one runtime per file. [`Pool`] instead
+//! shares a single threadpool across any number of [`PooledWriter`]s: each writer buffers input up
+//! to the block size and enqueues the block for compression on the shared pool, while a per-writer
+//! ordered queue of one-shot results keeps that destination's bytes in order.
+//!
+//! ```no_run
+//! use std::io::Write;
+//!
+//! use gzp::pool::Pool;
+//!
+//! let pool = Pool::new(16);
+//! let mut shard = pool.exchange(vec![]);
+//! shard.write_all(b"some bytes").unwrap();
+//! shard.finish().unwrap();
+//! ```
+use std::io::{self, Write};
+
+use std::sync::Arc;
+
+use bytes::BytesMut;
+use futures::executor::block_on;
+use tokio::runtime::{Handle, Runtime};
+use tokio::sync::{mpsc, oneshot, OwnedSemaphorePermit, Semaphore};
+
+use crate::compressor::{Compressor, Gzip};
+use crate::{Compression, ParGzError, BUFSIZE};
+
+/// A shared pool of compression threads usable by many [`PooledWriter`]s.
+///
+/// The pool owns a single tokio runtime, and compression across *all* writers is bounded to
+/// `num_threads` concurrent blocks by a shared [`Semaphore`], so the thread budget is fixed and
+/// decoupled from the number of writers. Per-writer ordering is handled by a lightweight async
+/// write task that parks as a suspended future, not a dedicated OS thread, while idle -- so
+/// thousands of writers can be open at once without exhausting the blocking pool -- and offloads the
+/// blocking `write_all` onto `spawn_blocking` only while it actually has bytes to write, keeping the
+/// worker threads free for the async machinery.
+pub struct Pool<C = Gzip> {
+    runtime: Runtime,
+    compression_level: Compression,
+    buffer_size: usize,
+    compressor: C,
+    /// Bounds the number of in-flight `compress_block` tasks across the whole pool to `num_threads`.
+    permits: Arc<Semaphore>,
+    /// Bounds each writer's ordered result queue, so a slow sink cannot buffer blocks without limit.
+    num_threads: usize,
+}
+
+impl Pool<Gzip> {
+    /// Create a new pool whose compression is bounded to `num_threads` concurrent blocks, producing
+    /// gzip output.
+    pub fn new(num_threads: usize) -> Self {
+        assert!(num_threads > 0);
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(num_threads)
+            .max_blocking_threads(num_threads)
+            .build()
+            .expect("failed to build pool runtime");
+        Self {
+            runtime,
+            compression_level: Compression::new(3),
+            buffer_size: BUFSIZE,
+            compressor: Gzip,
+            permits: Arc::new(Semaphore::new(num_threads)),
+            num_threads,
+        }
+    }
+}
+
+impl<C> Pool<C>
+where
+    C: Compressor,
+{
+    /// Set the compression level used by writers handed out from this pool.
+    pub fn compression_level(mut self, compression_level: Compression) -> Self {
+        self.compression_level = compression_level;
+        self
+    }
+
+    /// Set the block size writers accumulate before enqueuing a block for compression.
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        assert!(buffer_size > 0);
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// Set the output [`format`](Compressor) produced by writers handed out from this pool.
+    pub fn format<C2>(self, compressor: C2) -> Pool<C2>
+    where
+        C2: Compressor,
+    {
+        Pool {
+            runtime: self.runtime,
+            compression_level: self.compression_level,
+            buffer_size: self.buffer_size,
+            compressor,
+            permits: self.permits,
+            num_threads: self.num_threads,
+        }
+    }
+
+    /// Exchange a plain [`Write`] for a [`PooledWriter`] that compresses on this pool.
+    ///
+    /// The returned writer can be used in place of `writer`. Each destination's bytes are written
+    /// in order even though compression is shared across the whole pool.
+    pub fn exchange<W>(&self, writer: W) -> PooledWriter<C>
+    where
+        W: Write + Send + 'static,
+    {
+        // The ordered queue of per-block results feeding this writer's write task. It is bounded so a
+        // destination whose underlying writer is slower than compression applies backpressure instead
+        // of accumulating compressed blocks in memory without limit.
+        let (queue_tx, mut queue_rx) =
+            mpsc::channel::<oneshot::Receiver<Result<Vec<u8>, ParGzError>>>(self.num_threads);
+
+        // An async task, not a blocking thread: an idle writer is just a parked future, so opening
+        // many writers before finishing any cannot exhaust the pool. The blocking `write_all`/`flush`
+        // is pushed onto `spawn_blocking` so an actively-writing shard occupies a blocking thread for
+        // the duration of the write only, rather than pinning a runtime worker thread.
+        let handle = self.runtime.handle().clone();
+        let write_handle = self.runtime.spawn(async move {
+            let mut writer = Some(writer);
+            while let Some(result) = queue_rx.recv().await {
+                let bytes = result.await.map_err(|_e| ParGzError::ChannelSend)??;
+                let mut w = writer.take().expect("writer is held between writes");
+                writer = Some(
+                    handle
+                        .spawn_blocking(move || -> Result<W, ParGzError> {
+                            w.write_all(&bytes)?;
+                            Ok(w)
+                        })
+                        .await??,
+                );
+            }
+            let mut w = writer.take().expect("writer is held between writes");
+            handle
+                .spawn_blocking(move || -> Result<(), ParGzError> {
+                    w.flush()?;
+                    Ok(())
+                })
+                .await??;
+            Ok::<(), ParGzError>(())
+        });
+
+        PooledWriter {
+            handle: self.runtime.handle().clone(),
+            permits: Arc::clone(&self.permits),
+            queue_tx,
+            write_handle,
+            buffer: BytesMut::with_capacity(self.buffer_size),
+            buffer_size: self.buffer_size,
+            compression_level: self.compression_level,
+            compressor: self.compressor.clone(),
+        }
+    }
+}
+
+/// A [`Write`] whose compression is performed on a shared [`Pool`].
+///
+/// Bytes are buffered until a full block is available, then compressed on the pool and written to
+/// the underlying writer in order. [`PooledWriter::finish`] must be called to flush the final block
+/// and join the write task.
+pub struct PooledWriter<C = Gzip> {
+    handle: Handle,
+    permits: Arc<Semaphore>,
+    queue_tx: mpsc::Sender<oneshot::Receiver<Result<Vec<u8>, ParGzError>>>,
+    write_handle: tokio::task::JoinHandle<Result<(), ParGzError>>,
+    buffer: BytesMut,
+    buffer_size: usize,
+    compression_level: Compression,
+    compressor: C,
+}
+
+impl<C> PooledWriter<C>
+where
+    C: Compressor,
+{
+    /// Enqueue `block` for compression on the pool, reserving its slot in the ordered write queue.
+    ///
+    /// The ordered slot is reserved *before* compression starts, both so output stays in order and so
+    /// the bounded send blocks here when this destination's write queue is full -- the backpressure
+    /// that stops a slow sink from buffering compressed blocks without limit. A shared permit is then
+    /// acquired so at most `num_threads` blocks compress at once across every writer.
+    fn dispatch(&mut self, block: BytesMut) -> io::Result<()> {
+        let (done_tx, done_rx) = oneshot::channel();
+        block_on(self.queue_tx.send(done_rx))
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+        let permit = block_on(Arc::clone(&self.permits).acquire_owned())
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+        let compressor = self.compressor.clone();
+        let compression_level = self.compression_level;
+        self.handle.spawn_blocking(move || {
+            // Hold the permit for the duration of the compression, releasing it on the way out.
+            let _permit: OwnedSemaphorePermit = permit;
+            let result = compressor.compress_block(&block[..], compression_level);
+            // If the write task is already gone the receiver is dropped; nothing to do.
+            let _ = done_tx.send(result);
+        });
+        Ok(())
+    }
+
+    /// Flush any remaining buffered bytes and wait for all writes to this destination to finish.
+    ///
+    /// This *MUST* be called before the [`PooledWriter`] goes out of scope.
+    pub fn finish(mut self) -> Result<(), ParGzError> {
+        if !self.buffer.is_empty() {
+            let remaining = self.buffer.split();
+            self.dispatch(remaining)?;
+        }
+        drop(self.queue_tx);
+        block_on(self.write_handle)?
+    }
+}
+
+impl<C> Write for PooledWriter<C>
+where
+    C: Compressor,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        while self.buffer.len() > self.buffer_size {
+            let block = self.buffer.split_to(self.buffer_size);
+            self.dispatch(block)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            let block = self.buffer.split();
+            self.dispatch(block)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+    use std::io::{BufReader, BufWriter, Read};
+
+    use flate2::bufread::MultiGzDecoder;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_many_writers_one_pool() {
+        let dir = tempdir().unwrap();
+        let pool = Pool::new(2).buffer_size(16);
+
+        // Fan out to several shards sharing the same pool.
+        let inputs = [&b"first shard contents"[..], &b"second shard contents"[..]];
+        let mut files = Vec::new();
+        for (i, input) in inputs.iter().enumerate() {
+            let path = dir.path().join(format!("shard{i}.gz"));
+            let writer = BufWriter::new(File::create(&path).unwrap());
+            let mut shard = pool.exchange(writer);
+            shard.write_all(input).unwrap();
+            shard.finish().unwrap();
+            files.push(path);
+        }
+
+        for (path, input) in files.iter().zip(inputs.iter()) {
+            let mut reader = BufReader::new(File::open(path).unwrap());
+            let mut compressed = vec![];
+            reader.read_to_end(&mut compressed).unwrap();
+
+            let mut gz = MultiGzDecoder::new(&compressed[..]);
+            let mut bytes = vec![];
+            gz.read_to_end(&mut bytes).unwrap();
+            assert_eq!(&bytes, input);
+        }
+    }
+
+    #[test]
+    fn test_many_open_writers_do_not_deadlock() {
+        // Open far more writers than the pool's thread budget before finishing any of them. Because
+        // idle write tasks are parked futures rather than blocking threads, this must not deadlock.
+        let dir = tempdir().unwrap();
+        let pool = Pool::new(2).buffer_size(16);
+
+        let mut shards = Vec::new();
+        let mut paths = Vec::new();
+        for i in 0..32 {
+            let path = dir.path().join(format!("shard{i}.gz"));
+            let mut shard = pool.exchange(BufWriter::new(File::create(&path).unwrap()));
+            shard
+                .write_all(format!("contents of shard {i}").as_bytes())
+                .unwrap();
+            shards.push(shard);
+            paths.push(path);
+        }
+
+        for shard in shards {
+            shard.finish().unwrap();
+        }
+
+        for (i, path) in paths.iter().enumerate() {
+            let mut reader = BufReader::new(File::open(path).unwrap());
+            let mut compressed = vec![];
+            reader.read_to_end(&mut compressed).unwrap();
+
+            let mut gz = MultiGzDecoder::new(&compressed[..]);
+            let mut bytes = vec![];
+            gz.read_to_end(&mut bytes).unwrap();
+            assert_eq!(bytes, format!("contents of shard {i}").into_bytes());
+        }
+    }
+}
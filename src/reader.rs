@@ -0,0 +1,858 @@
+//! Parallel multi-member gzip decompression.
+//!
+//! [`ParGz`](crate::ParGz) emits one independent gzip member per buffered block, so the members can
+//! be decompressed concurrently and stitched back together in order. [`ParGzReader`] carves the
+//! input into members -- finding each boundary by parsing the gzip header and walking the DEFLATE
+//! block structure rather than scanning for the `1f 8b` magic, so a magic sequence inside a member's
+//! DEFLATE payload cannot truncate it and no member is ever inflated twice -- and dispatches each
+//! carved member to its own `spawn_blocking` decode task on a tokio runtime. The tasks are
+//! reassembled in the original order through a channel bounded to `num_threads`, so about
+//! `num_threads` members decode in parallel while the compressed input and decompressed output are
+//! both streamed: only the members currently in flight are held in memory.
+//!
+//! This also reads any multi-member gzip file, such as those produced by `pigz`.
+use std::io::{self, BufRead, BufReader, Read};
+
+use bytes::Bytes;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::ParGzError;
+
+/// The [`ParGzReader`] builder.
+#[derive(Debug)]
+pub struct ParGzReaderBuilder<R> {
+    /// The underlying reader to pull compressed bytes from.
+    reader: R,
+    /// The number of threads to use for decompression. Defaults to all available threads.
+    num_threads: usize,
+}
+
+impl<R> ParGzReaderBuilder<R>
+where
+    R: Read,
+{
+    /// Create a new [`ParGzReaderBuilder`] object.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            num_threads: num_cpus::get(),
+        }
+    }
+
+    /// Set the [`num_threads`](ParGzReaderBuilder.num_threads).
+    pub fn num_threads(mut self, num_threads: usize) -> Self {
+        assert!(num_threads > 0);
+        self.num_threads = num_threads;
+        self
+    }
+
+    /// Decompress the input member by member and hand back a streaming [`ParGzReader`].
+    pub fn build(self) -> Result<ParGzReader, ParGzError>
+    where
+        R: Send + 'static,
+    {
+        ParGzReader::new(self.reader, self.num_threads)
+    }
+}
+
+/// A parallel decoder for multi-member gzip streams.
+///
+/// Construct one with [`ParGzReader::builder`]. A carver task peels one gzip member off the input at
+/// a time and dispatches each to its own `spawn_blocking` decode task, sending the tasks -- in order
+/// -- down a channel bounded to `num_threads`; the [`Read`] impl awaits them in turn, so the members
+/// queued in the channel, the one being read and the one the carver is about to send (at most
+/// `num_threads + 2`) decode concurrently while neither the compressed input nor the decompressed
+/// output is buffered in full.
+pub struct ParGzReader {
+    /// Kept alive so the carver and decode tasks keep running for the reader's lifetime.
+    runtime: tokio::runtime::Runtime,
+    /// Ordered decode tasks; the carver also uses this to surface a carve/IO failure.
+    receiver: mpsc::Receiver<JoinHandle<Result<Bytes, ParGzError>>>,
+    /// The decoded member currently being served to the caller.
+    current: io::Cursor<Bytes>,
+    /// Set once the input has been fully consumed.
+    done: bool,
+}
+
+impl ParGzReader {
+    /// Create a builder to configure the decoder.
+    pub fn builder<R>(reader: R) -> ParGzReaderBuilder<R>
+    where
+        R: Read,
+    {
+        ParGzReaderBuilder::new(reader)
+    }
+
+    /// Spawn the carver task and wire up the ordered channel.
+    fn new<R>(reader: R, num_threads: usize) -> Result<Self, ParGzError>
+    where
+        R: Read + Send + 'static,
+    {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(num_threads)
+            .build()?;
+
+        // Bound the channel by the thread budget so the carver stays at most `num_threads` members
+        // ahead of the consumer, keeping memory proportional to `num_threads` blocks.
+        let (sender, receiver) = mpsc::channel(num_threads);
+        let handle = runtime.handle().clone();
+        runtime.spawn_blocking(move || carve_members(reader, handle, sender));
+
+        Ok(Self {
+            runtime,
+            receiver,
+            current: io::Cursor::new(Bytes::new()),
+            done: false,
+        })
+    }
+}
+
+impl Read for ParGzReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.current.read(buf)?;
+            if read > 0 {
+                return Ok(read);
+            }
+            if self.done {
+                return Ok(0);
+            }
+            match self.runtime.block_on(self.receiver.recv()) {
+                Some(task) => match self.runtime.block_on(task) {
+                    Ok(Ok(member)) => self.current = io::Cursor::new(member),
+                    Ok(Err(e)) => {
+                        self.done = true;
+                        return Err(to_io(e));
+                    }
+                    Err(e) => {
+                        self.done = true;
+                        return Err(to_io(e.into()));
+                    }
+                },
+                None => {
+                    self.done = true;
+                    return Ok(0);
+                }
+            }
+        }
+    }
+}
+
+/// Carve `reader` into gzip members and fan each one out to its own decode task, in order.
+///
+/// Each member's boundary is found by [`scan_member`], which parses the gzip header and walks the
+/// DEFLATE block structure without producing any output, while a [`Recorder`] captures that member's
+/// raw bytes. The captured bytes are handed to a `spawn_blocking` task that performs the one and only
+/// inflate, so members decode in parallel even though the boundaries are discovered in order.
+fn carve_members<R: Read>(
+    reader: R,
+    handle: Handle,
+    sender: mpsc::Sender<JoinHandle<Result<Bytes, ParGzError>>>,
+) {
+    let mut input = BufReader::new(reader);
+    loop {
+        match input.fill_buf() {
+            Ok(buf) if buf.is_empty() => return,
+            Ok(_) => {}
+            Err(e) => {
+                let task = handle.spawn_blocking(move || -> Result<Bytes, ParGzError> { Err(ParGzError::from(e)) });
+                let _ = sender.blocking_send(task);
+                return;
+            }
+        }
+
+        // Advance over exactly one member, capturing its raw bytes as we go.
+        let mut recorder = Recorder::new(&mut input);
+        if let Err(e) = scan_member(&mut recorder) {
+            let task = handle.spawn_blocking(move || -> Result<Bytes, ParGzError> { Err(ParGzError::from(e)) });
+            let _ = sender.blocking_send(task);
+            return;
+        }
+        let member = recorder.into_recorded();
+
+        let task = handle.spawn_blocking(move || -> Result<Bytes, ParGzError> {
+            let mut out = Vec::with_capacity(member.len() * 2);
+            let mut decoder = flate2::read::GzDecoder::new(&member[..]);
+            decoder.read_to_end(&mut out)?;
+            Ok(Bytes::from(out))
+        });
+        if sender.blocking_send(task).is_err() {
+            return;
+        }
+    }
+}
+
+/// Advance over exactly one gzip member off `input` without decompressing it.
+///
+/// We only need to find where the member ends so the [`Recorder`] beneath `input` captures its
+/// compressed bytes; the real inflate happens once, later, on the threadpool. The boundary is found
+/// by parsing the gzip header, walking each DEFLATE block (decoding the Huffman bitstream only far
+/// enough to reach the end-of-block marker, never materializing output), and consuming the 8-byte
+/// gzip trailer.
+fn scan_member<R: Read>(input: &mut R) -> io::Result<()> {
+    let mut scanner = MemberScanner::new(input);
+    scanner.skip_header()?;
+    scanner.skip_deflate()?;
+    scanner.skip_trailer()
+}
+
+/// Maximum number of bits in a DEFLATE Huffman code.
+const MAX_BITS: usize = 15;
+/// Number of literal/length codes in the fixed Huffman alphabet.
+const FIXED_LIT_CODES: usize = 288;
+/// Number of distance codes.
+const MAX_DIST_CODES: usize = 30;
+/// Extra bits carried by each length symbol (257..=285).
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+/// Extra bits carried by each distance symbol (0..=29).
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+/// Order in which the code-length code lengths appear in a dynamic block header.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// A canonical Huffman decode table, built from a list of per-symbol code lengths.
+///
+/// `count[n]` is the number of codes of length `n`; `symbol` holds the symbols ordered first by code
+/// length and then by symbol value, the layout [`MemberScanner::decode`] walks. This mirrors the
+/// table construction in zlib's `puff.c` reference inflater.
+struct Huffman {
+    count: [u16; MAX_BITS + 1],
+    symbol: Vec<u16>,
+}
+
+impl Huffman {
+    /// Build a decode table from `lengths`, where `lengths[sym]` is the code length of `sym` (0 means
+    /// the symbol is unused).
+    fn new(lengths: &[u16]) -> Self {
+        let mut count = [0u16; MAX_BITS + 1];
+        for &len in lengths {
+            count[len as usize] += 1;
+        }
+        count[0] = 0;
+        let mut offsets = [0u16; MAX_BITS + 1];
+        for len in 1..MAX_BITS {
+            offsets[len + 1] = offsets[len] + count[len];
+        }
+        let mut symbol = vec![0u16; lengths.len()];
+        for (sym, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbol[offsets[len as usize] as usize] = sym as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Self { count, symbol }
+    }
+}
+
+/// Walks a single gzip member's bytes, decoding the DEFLATE bitstream only far enough to find each
+/// block boundary. DEFLATE reads bits least-significant-first; `bitbuf` holds the bits pulled from
+/// `input` but not yet consumed, with `bitcnt` of them valid.
+struct MemberScanner<'a, R> {
+    input: &'a mut R,
+    bitbuf: u64,
+    bitcnt: u32,
+}
+
+impl<'a, R: Read> MemberScanner<'a, R> {
+    fn new(input: &'a mut R) -> Self {
+        Self {
+            input,
+            bitbuf: 0,
+            bitcnt: 0,
+        }
+    }
+
+    /// Read one raw byte straight from `input`, erroring on a truncated member.
+    fn read_u8(&mut self) -> io::Result<u8> {
+        let mut byte = [0u8; 1];
+        self.input.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    /// Pull `n` bits (`n <= 32`) from the stream, LSB-first, refilling `bitbuf` a byte at a time.
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        while self.bitcnt < n {
+            let byte = self.read_u8()? as u64;
+            self.bitbuf |= byte << self.bitcnt;
+            self.bitcnt += 8;
+        }
+        let mask = if n == 0 { 0 } else { (1u64 << n) - 1 };
+        let value = (self.bitbuf & mask) as u32;
+        self.bitbuf >>= n;
+        self.bitcnt -= n;
+        Ok(value)
+    }
+
+    /// Discard any sub-byte bits so the next read starts on a byte boundary.
+    fn align_to_byte(&mut self) {
+        let drop = self.bitcnt & 7;
+        self.bitbuf >>= drop;
+        self.bitcnt -= drop;
+    }
+
+    /// Read the next whole byte, draining `bitbuf` first when it already holds buffered bytes.
+    fn next_byte(&mut self) -> io::Result<u8> {
+        if self.bitcnt >= 8 {
+            let byte = (self.bitbuf & 0xff) as u8;
+            self.bitbuf >>= 8;
+            self.bitcnt -= 8;
+            Ok(byte)
+        } else {
+            self.read_u8()
+        }
+    }
+
+    /// Consume the gzip header, skipping over the optional extra/name/comment/HCRC fields.
+    fn skip_header(&mut self) -> io::Result<()> {
+        if self.read_u8()? != 0x1f || self.read_u8()? != 0x8b {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a gzip member"));
+        }
+        let _cm = self.read_u8()?;
+        let flags = self.read_u8()?;
+        for _ in 0..6 {
+            // MTIME (4), XFL (1), OS (1).
+            self.read_u8()?;
+        }
+        if flags & 0x04 != 0 {
+            let xlen = self.read_u8()? as usize | ((self.read_u8()? as usize) << 8);
+            for _ in 0..xlen {
+                self.read_u8()?;
+            }
+        }
+        if flags & 0x08 != 0 {
+            while self.read_u8()? != 0 {}
+        }
+        if flags & 0x10 != 0 {
+            while self.read_u8()? != 0 {}
+        }
+        if flags & 0x02 != 0 {
+            self.read_u8()?;
+            self.read_u8()?;
+        }
+        Ok(())
+    }
+
+    /// Walk every DEFLATE block until the one flagged final, without producing output.
+    fn skip_deflate(&mut self) -> io::Result<()> {
+        // The fixed tables are constant; build them at most once per member and reuse.
+        let mut fixed: Option<(Huffman, Huffman)> = None;
+        loop {
+            let final_block = self.bits(1)? == 1;
+            match self.bits(2)? {
+                0 => self.skip_stored_block()?,
+                1 => {
+                    let (lit, dist) =
+                        fixed.get_or_insert_with(|| (fixed_lit_huffman(), fixed_dist_huffman()));
+                    self.skip_compressed_block(lit, dist)?;
+                }
+                2 => {
+                    let (lit, dist) = self.read_dynamic_tables()?;
+                    self.skip_compressed_block(&lit, &dist)?;
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "invalid DEFLATE block type",
+                    ))
+                }
+            }
+            if final_block {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Skip a stored (uncompressed) block: byte-align, check LEN against its complement NLEN, then
+    /// skip LEN literal bytes. A mismatched NLEN means the boundary is being mis-read, so reject it
+    /// at the fault point rather than carving a wrong member length.
+    fn skip_stored_block(&mut self) -> io::Result<()> {
+        self.align_to_byte();
+        let len = self.next_byte()? as u16 | ((self.next_byte()? as u16) << 8);
+        let nlen = self.next_byte()? as u16 | ((self.next_byte()? as u16) << 8);
+        if nlen != !len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "stored block length does not match its complement",
+            ));
+        }
+        for _ in 0..len {
+            self.next_byte()?;
+        }
+        Ok(())
+    }
+
+    /// Skip a Huffman-coded block, decoding length/distance pairs just to consume their extra bits.
+    fn skip_compressed_block(&mut self, lit: &Huffman, dist: &Huffman) -> io::Result<()> {
+        loop {
+            let symbol = self.decode(lit)?;
+            if symbol == 256 {
+                return Ok(());
+            }
+            if symbol < 256 {
+                continue;
+            }
+            let length_index = (symbol - 257) as usize;
+            let extra = *LENGTH_EXTRA.get(length_index).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "invalid length symbol")
+            })?;
+            self.bits(extra as u32)?;
+            let dist_symbol = self.decode(dist)? as usize;
+            let extra = *DIST_EXTRA.get(dist_symbol).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "invalid distance symbol")
+            })?;
+            self.bits(extra as u32)?;
+        }
+    }
+
+    /// Read a dynamic block's code-length tables and return its literal/length and distance tables.
+    fn read_dynamic_tables(&mut self) -> io::Result<(Huffman, Huffman)> {
+        let hlit = self.bits(5)? as usize + 257;
+        let hdist = self.bits(5)? as usize + 1;
+        let hclen = self.bits(4)? as usize + 4;
+
+        let mut code_lengths = [0u16; 19];
+        for &slot in CODE_LENGTH_ORDER.iter().take(hclen) {
+            code_lengths[slot] = self.bits(3)? as u16;
+        }
+        let code_length_huffman = Huffman::new(&code_lengths);
+
+        let mut lengths = vec![0u16; hlit + hdist];
+        let mut index = 0;
+        while index < lengths.len() {
+            let (value, count) = match self.decode(&code_length_huffman)? {
+                symbol @ 0..=15 => (symbol, 1),
+                16 => {
+                    let prev = *lengths.get(index.wrapping_sub(1)).ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidData, "repeat with no prior length")
+                    })?;
+                    (prev, self.bits(2)? as usize + 3)
+                }
+                17 => (0, self.bits(3)? as usize + 3),
+                18 => (0, self.bits(7)? as usize + 11),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "invalid code-length symbol",
+                    ))
+                }
+            };
+            for _ in 0..count {
+                *lengths.get_mut(index).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "code-length repeat overruns table")
+                })? = value;
+                index += 1;
+            }
+        }
+
+        Ok((
+            Huffman::new(&lengths[..hlit]),
+            Huffman::new(&lengths[hlit..]),
+        ))
+    }
+
+    /// Decode one symbol from the bitstream using `huffman`, bit by bit (the `puff.c` algorithm).
+    fn decode(&mut self, huffman: &Huffman) -> io::Result<u16> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..=MAX_BITS {
+            code |= self.bits(1)? as i32;
+            let count = huffman.count[len] as i32;
+            if code - count < first {
+                // A well-formed table keeps this index within `symbol`; a malformed (incomplete or
+                // over-subscribed) one may not, so bounds-check rather than panic on bad input.
+                let symbol = (index + (code - first)) as usize;
+                return huffman.symbol.get(symbol).copied().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "invalid Huffman code")
+                });
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid Huffman code",
+        ))
+    }
+
+    /// Byte-align and consume the 8-byte gzip trailer (CRC32 + ISIZE).
+    fn skip_trailer(&mut self) -> io::Result<()> {
+        self.align_to_byte();
+        for _ in 0..8 {
+            self.next_byte()?;
+        }
+        Ok(())
+    }
+}
+
+/// The fixed literal/length Huffman table defined by RFC 1951 section 3.2.6.
+fn fixed_lit_huffman() -> Huffman {
+    let mut lengths = [0u16; FIXED_LIT_CODES];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    Huffman::new(&lengths)
+}
+
+/// The fixed distance Huffman table: 30 codes, all five bits long.
+fn fixed_dist_huffman() -> Huffman {
+    Huffman::new(&[5u16; MAX_DIST_CODES])
+}
+
+/// A [`BufRead`] wrapper that records every byte consumed through it.
+///
+/// Wrapping the shared [`BufReader`] while a member is scanned captures that member's exact
+/// compressed bytes, which can then be decoded independently on the threadpool.
+struct Recorder<'a, R> {
+    inner: &'a mut R,
+    recorded: Vec<u8>,
+}
+
+impl<'a, R: BufRead> Recorder<'a, R> {
+    fn new(inner: &'a mut R) -> Self {
+        Self {
+            inner,
+            recorded: Vec::new(),
+        }
+    }
+
+    fn into_recorded(self) -> Vec<u8> {
+        self.recorded
+    }
+}
+
+impl<R: BufRead> Read for Recorder<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.inner.fill_buf()?;
+        let n = buf.len().min(available.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.recorded.extend_from_slice(&available[..n]);
+        self.inner.consume(n);
+        Ok(n)
+    }
+}
+
+/// Surface a [`ParGzError`] through the [`Read`] impl, preserving the underlying [`io::Error`].
+fn to_io(e: ParGzError) -> io::Error {
+    match e {
+        ParGzError::Io(e) => e,
+        other => io::Error::new(io::ErrorKind::Other, other),
+    }
+}
+
+/// A compression algorithm recognized by [`DetectDecoder`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// gzip (`1f 8b`).
+    Gzip,
+    /// bzip2 (`BZh`).
+    Bzip2,
+    /// xz (`fd 37 7a 58 5a 00`).
+    Xz,
+    /// zstandard (`28 b5 2f fd`).
+    Zstd,
+    /// No known magic matched; the input is passed through unchanged.
+    Unknown,
+}
+
+impl Algorithm {
+    /// Identify the algorithm from the leading bytes of a stream.
+    fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Algorithm::Gzip
+        } else if bytes.starts_with(b"BZh") {
+            Algorithm::Bzip2
+        } else if bytes.starts_with(&[0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00]) {
+            Algorithm::Xz
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Algorithm::Zstd
+        } else {
+            Algorithm::Unknown
+        }
+    }
+}
+
+/// A decoder that sniffs the input's magic number and decompresses it with the matching algorithm.
+///
+/// Only the handful of bytes needed to decide are buffered; they are then chained back in front of
+/// the rest of the input so no data is lost. gzip input is streamed through [`ParGzReader`]; the
+/// other formats use their streaming decoders. Unrecognized input is passed through unchanged.
+/// The detected [`Algorithm`] is available via [`DetectDecoder::algorithm`].
+pub struct DetectDecoder {
+    algorithm: Algorithm,
+    inner: Box<dyn Read>,
+}
+
+impl DetectDecoder {
+    /// The longest magic we need to buffer to decide (xz is six bytes).
+    const MAGIC_LEN: usize = 6;
+
+    /// Sniff `reader` and build the matching decoder.
+    pub fn new<R>(mut reader: R) -> Result<Self, ParGzError>
+    where
+        R: Read + Send + 'static,
+    {
+        let mut magic = [0u8; Self::MAGIC_LEN];
+        let read = fill_buf(&mut reader, &mut magic)?;
+        let prefix = magic[..read].to_vec();
+        let algorithm = Algorithm::detect(&prefix);
+
+        // Put the sniffed bytes back in front of the remaining input.
+        let chained = io::Cursor::new(prefix).chain(reader);
+        // gzp's own `Bzip2`/`Xz`/`Zstd` compressors emit one independent frame per block, so the
+        // detect path must use the multi-stream decoders that consume every concatenated frame --
+        // the single-stream variants would silently stop after the first block. `zstd`'s streaming
+        // `Decoder` already reads through all frames (the behavior `decode_all` relies on).
+        let inner: Box<dyn Read> = match algorithm {
+            Algorithm::Gzip => Box::new(ParGzReader::builder(chained).build()?),
+            Algorithm::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(chained)),
+            Algorithm::Xz => Box::new(xz2::read::XzDecoder::new_multi_decoder(chained)),
+            Algorithm::Zstd => Box::new(zstd::stream::read::Decoder::new(chained)?),
+            Algorithm::Unknown => Box::new(chained),
+        };
+
+        Ok(Self { algorithm, inner })
+    }
+
+    /// The algorithm that was detected from the input's magic number.
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+}
+
+impl Read for DetectDecoder {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+/// Read from `reader` until `buf` is full or EOF, returning the number of bytes read.
+fn fill_buf<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn test_read_multiple_members() {
+        // Concatenate two independent members, as `ParGz` would, and make sure both are recovered
+        // and reassembled in order.
+        let mut encoded = vec![];
+        for part in [&b"first chunk"[..], &b"second chunk"[..]] {
+            let mut gz = flate2::write::GzEncoder::new(&mut encoded, crate::Compression::new(3));
+            gz.write_all(part).unwrap();
+            gz.finish().unwrap();
+        }
+
+        let mut reader = ParGzReader::builder(io::Cursor::new(encoded)).build().unwrap();
+        let mut out = vec![];
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"first chunksecond chunk");
+    }
+
+    #[test]
+    fn test_read_single_member() {
+        let mut encoded = vec![];
+        {
+            let mut gz = flate2::write::GzEncoder::new(&mut encoded, crate::Compression::new(3));
+            gz.write_all(b"hello world").unwrap();
+            gz.finish().unwrap();
+        }
+
+        let mut reader = ParGzReader::builder(io::Cursor::new(encoded))
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let mut out = vec![];
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"hello world");
+    }
+
+    #[test]
+    fn test_magic_inside_payload_is_not_a_boundary() {
+        // A single member whose payload contains the `1f 8b 08 00` sequence must still decode as
+        // one member -- the old magic scan would have split it and corrupted the output.
+        let payload = {
+            let mut v = vec![0u8; 4096];
+            v[1000..1004].copy_from_slice(&[0x1f, 0x8b, 0x08, 0x00]);
+            v
+        };
+        let mut encoded = vec![];
+        {
+            let mut gz = flate2::write::GzEncoder::new(&mut encoded, crate::Compression::new(0));
+            gz.write_all(&payload).unwrap();
+            gz.finish().unwrap();
+        }
+
+        let mut reader = ParGzReader::builder(io::Cursor::new(encoded)).build().unwrap();
+        let mut out = vec![];
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, payload);
+    }
+
+    #[test]
+    fn test_detect_gzip() {
+        let mut encoded = vec![];
+        {
+            let mut gz = flate2::write::GzEncoder::new(&mut encoded, crate::Compression::new(3));
+            gz.write_all(b"detect me").unwrap();
+            gz.finish().unwrap();
+        }
+
+        let mut decoder = DetectDecoder::new(io::Cursor::new(encoded)).unwrap();
+        assert_eq!(decoder.algorithm(), Algorithm::Gzip);
+        let mut out = vec![];
+        decoder.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"detect me");
+    }
+
+    #[test]
+    fn test_detect_bzip2() {
+        let mut encoded = vec![];
+        {
+            let mut enc = bzip2::write::BzEncoder::new(&mut encoded, bzip2::Compression::best());
+            enc.write_all(b"bzip2 payload").unwrap();
+            enc.finish().unwrap();
+        }
+
+        let mut decoder = DetectDecoder::new(io::Cursor::new(encoded)).unwrap();
+        assert_eq!(decoder.algorithm(), Algorithm::Bzip2);
+        let mut out = vec![];
+        decoder.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"bzip2 payload");
+    }
+
+    #[test]
+    fn test_detect_xz() {
+        let mut encoded = vec![];
+        {
+            let mut enc = xz2::write::XzEncoder::new(&mut encoded, 6);
+            enc.write_all(b"xz payload").unwrap();
+            enc.finish().unwrap();
+        }
+
+        let mut decoder = DetectDecoder::new(io::Cursor::new(encoded)).unwrap();
+        assert_eq!(decoder.algorithm(), Algorithm::Xz);
+        let mut out = vec![];
+        decoder.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"xz payload");
+    }
+
+    #[test]
+    fn test_detect_zstd() {
+        let encoded = zstd::stream::encode_all(&b"zstd payload"[..], 6).unwrap();
+
+        let mut decoder = DetectDecoder::new(io::Cursor::new(encoded)).unwrap();
+        assert_eq!(decoder.algorithm(), Algorithm::Zstd);
+        let mut out = vec![];
+        decoder.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"zstd payload");
+    }
+
+    #[test]
+    fn test_detect_unknown_passthrough() {
+        let raw = b"not a compressed stream";
+        let mut decoder = DetectDecoder::new(io::Cursor::new(raw.to_vec())).unwrap();
+        assert_eq!(decoder.algorithm(), Algorithm::Unknown);
+        let mut out = vec![];
+        decoder.read_to_end(&mut out).unwrap();
+        assert_eq!(out, raw);
+    }
+
+    #[test]
+    fn test_read_many_members_ordered() {
+        // Many members decoded across several threads must still reassemble in order.
+        let mut encoded = vec![];
+        let parts: Vec<String> = (0..16).map(|i| format!("member number {i:02}\n")).collect();
+        for part in &parts {
+            let mut gz = flate2::write::GzEncoder::new(&mut encoded, crate::Compression::new(6));
+            gz.write_all(part.as_bytes()).unwrap();
+            gz.finish().unwrap();
+        }
+
+        let mut reader = ParGzReader::builder(io::Cursor::new(encoded))
+            .num_threads(4)
+            .build()
+            .unwrap();
+        let mut out = vec![];
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, parts.concat().into_bytes());
+    }
+
+    /// Compress `input` with `ParGz` in the given format using a tiny block size, so the output is
+    /// several independent frames concatenated -- the multi-block shape the detect path must handle.
+    fn pargz_frames<C: crate::Compressor>(input: &[u8], format: C) -> Vec<u8> {
+        use std::fs::File;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data");
+        {
+            let mut par = crate::ParGz::builder(File::create(&path).unwrap())
+                .format(format)
+                .buffer_size(16)
+                .build()
+                .unwrap();
+            par.write_all(input).unwrap();
+            par.finish().unwrap();
+        }
+        std::fs::read(&path).unwrap()
+    }
+
+    #[test]
+    fn test_detect_multi_block_bzip2() {
+        let input = b"a payload long enough to be split across several independent bzip2 frames";
+        let encoded = pargz_frames(&input[..], crate::Bzip2);
+        let mut decoder = DetectDecoder::new(io::Cursor::new(encoded)).unwrap();
+        assert_eq!(decoder.algorithm(), Algorithm::Bzip2);
+        let mut out = vec![];
+        decoder.read_to_end(&mut out).unwrap();
+        assert_eq!(out, input);
+    }
+
+    #[test]
+    fn test_detect_multi_block_xz() {
+        let input = b"a payload long enough to be split across several independent xz frames!!";
+        let encoded = pargz_frames(&input[..], crate::Xz);
+        let mut decoder = DetectDecoder::new(io::Cursor::new(encoded)).unwrap();
+        assert_eq!(decoder.algorithm(), Algorithm::Xz);
+        let mut out = vec![];
+        decoder.read_to_end(&mut out).unwrap();
+        assert_eq!(out, input);
+    }
+
+    #[test]
+    fn test_detect_multi_block_zstd() {
+        let input = b"a payload long enough to be split across several independent zstd frames";
+        let encoded = pargz_frames(&input[..], crate::Zstd);
+        let mut decoder = DetectDecoder::new(io::Cursor::new(encoded)).unwrap();
+        assert_eq!(decoder.algorithm(), Algorithm::Zstd);
+        let mut out = vec![];
+        decoder.read_to_end(&mut out).unwrap();
+        assert_eq!(out, input);
+    }
+}